@@ -20,9 +20,14 @@
 //! ```
 #![warn(missing_docs)]
 
+extern crate bincode;
 extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 use std::borrow::ToOwned;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::fs::File;
@@ -32,30 +37,112 @@ use std::io::prelude::*;
 use std::iter::Map;
 use std::path::Path;
 use std::rc::Rc;
-use rand::{Rng, thread_rng};
+use rand::{Rng, SeedableRng, StdRng};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
 /// The definition of all types that can be used in a Chain.
 pub trait Chainable: Eq + Hash {}
 impl<T> Chainable for T where T: Eq + Hash {}
 
+/// Describes how to read tokens of a given type out of whitespace-delimited text, so that any
+/// `Chain<T>` can be trained directly from a string or file rather than forcing the caller to
+/// parse by hand. A token may span more than one whitespace-delimited word — `words_count`
+/// reports how many — which allows composite tokens such as coordinate pairs.
+pub trait FromTokens {
+    /// The token type produced. This is what the resulting `Chain` is parameterised over.
+    type Output: Chainable;
+    /// The number of whitespace-delimited words consumed to build a single token.
+    fn words_count() -> usize;
+    /// Builds a token from exactly `words_count()` words, returning a descriptive error on failure
+    /// instead of panicking.
+    fn read_words(words: &[&str]) -> Result<Self::Output, String>;
+}
+
+macro_rules! from_tokens_via_parse {
+    ($($t:ty),*) => {$(
+        impl FromTokens for $t {
+            type Output = $t;
+            fn words_count() -> usize { 1 }
+            fn read_words(words: &[&str]) -> Result<$t, String> {
+                words[0].parse::<$t>().map_err(|e| format!("{}", e))
+            }
+        }
+    )*}
+}
+
+from_tokens_via_parse!(i8, i16, i32, i64, u8, u16, u32, u64, usize, isize);
+
+impl FromTokens for char {
+    type Output = char;
+    fn words_count() -> usize { 1 }
+    fn read_words(words: &[&str]) -> Result<char, String> {
+        let mut chars = words[0].chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(format!("expected a single character, found {:?}", words[0])),
+        }
+    }
+}
+
+impl FromTokens for String {
+    type Output = String;
+    fn words_count() -> usize { 1 }
+    fn read_words(words: &[&str]) -> Result<String, String> {
+        Ok(words[0].to_owned())
+    }
+}
+
 /// A generic [Markov chain](https://en.wikipedia.org/wiki/Markov_chain) for almost any type. This
 /// uses HashMaps internally, and so Eq and Hash are both required.
-#[derive(PartialEq, Debug)]
 pub struct Chain<T> where T: Chainable {
-    map: HashMap<Vec<Option<Rc<T>>>, HashMap<Option<Rc<T>>, usize>>,
+    map: HashMap<Vec<Option<Rc<T>>>, States<T>>,
     order: usize,
+    rng: RefCell<StdRng>,
+}
+
+// Two chains are equal when their transition maps and orders match; the random number generator is
+// generation state, not part of the chain's identity.
+impl<T> PartialEq for Chain<T> where T: Chainable {
+    fn eq(&self, other: &Chain<T>) -> bool {
+        self.order == other.order && self.map == other.map
+    }
+}
+
+impl<T> ::std::fmt::Debug for Chain<T> where T: Chainable + ::std::fmt::Debug {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Chain")
+         .field("map", &self.map)
+         .field("order", &self.order)
+         .finish()
+    }
 }
 
 impl<T> Chain<T> where T: Chainable {
-    /// Constructs a new Markov chain.
+    /// Constructs a new Markov chain seeded from the operating system's entropy source.
     pub fn new() -> Chain<T> {
+        Chain::with_rng(StdRng::new().unwrap())
+    }
+
+    /// Constructs a new Markov chain driven by an explicitly seeded random number generator, so a
+    /// single chain's sequence of `generate` calls is repeatable for a given seed. Note that the
+    /// successor layout depends on each `HashMap`'s randomized iteration order, so two *separately
+    /// constructed* chains do not produce identical output even from the same seed; reuse one chain
+    /// for reproducibility, or pass a generator to `generate_with`.
+    pub fn from_seed(seed: &[usize]) -> Chain<T> {
+        Chain::with_rng(SeedableRng::from_seed(seed))
+    }
+
+    /// Constructs a new Markov chain that draws from the supplied random number generator.
+    pub fn with_rng(rng: StdRng) -> Chain<T> {
         Chain {
             map: {
                 let mut map = HashMap::new();
-                map.insert(vec!(None; 1), HashMap::new());
+                map.insert(vec!(None; 1), States::new());
                 map
             },
             order: 1,
+            rng: RefCell::new(rng),
         }
     }
 
@@ -64,14 +151,14 @@ impl<T> Chain<T> where T: Chainable {
     pub fn order(&mut self, order: usize) -> &mut Chain<T> {
         assert!(order > 0);
         self.order = order;
-        self.map.insert(vec!(None; self.order), HashMap::new());
+        self.map.insert(vec!(None; self.order), States::new());
         self
     }
 
     /// Determines whether or not the chain is empty. A chain is considered empty if nothing has
     /// been fed into it.
     pub fn is_empty(&self) -> bool {
-        self.map[&vec!(None; self.order)].is_empty()
+        self.map[&vec!(None; self.order)].counts.is_empty()
     }
 
 
@@ -86,21 +173,31 @@ impl<T> Chain<T> where T: Chainable {
         toks.push(None);
         for p in toks.windows(self.order + 1) {
             if !self.map.contains_key(&p[0..self.order].to_vec()) {
-                self.map.insert(p[0..self.order].to_vec(), HashMap::new());
+                self.map.insert(p[0..self.order].to_vec(), States::new());
             }
             self.map.get_mut(&p[0..self.order].to_vec()).unwrap().add(p[self.order].clone());
         }
         self
     }
 
-    /// Generates a collection of tokens from the chain. This operation is O(mn) where m is the
-    /// length of the generated collection, and n is the number of possible states from a given
-    /// state.
+    /// Generates a collection of tokens from the chain. This operation is O(m) after the alias
+    /// tables have been built, where m is the length of the generated collection, since each step
+    /// samples the next token in O(1). Uses the chain's own random number generator; seed it with
+    /// `Chain::from_seed` or pass a generator explicitly to `generate_with` for reproducible output.
     pub fn generate(&self) -> Vec<Rc<T>> {
+        let mut rng = self.rng.borrow_mut();
+        self.generate_with(&mut *rng)
+    }
+
+    /// Generates a collection of tokens from the chain using the supplied random number generator.
+    /// Passing a seeded generator (e.g. `StdRng::from_seed(..)`) makes the output deterministic,
+    /// which is useful for tests.
+    pub fn generate_with<R: Rng>(&self, rng: &mut R) -> Vec<Rc<T>> {
         let mut ret = Vec::new();
         let mut curs = vec!(None; self.order);
         loop {
-            let next = self.map[&curs].next();
+            // A state pruned out of the map is treated as terminal rather than indexed.
+            let next = self.map.get(&curs).and_then(|states| states.next(rng));
             curs = curs[1..self.order].to_vec();
             curs.push(next.clone());
             if let Some(next) = next { ret.push(next) };
@@ -110,16 +207,23 @@ impl<T> Chain<T> where T: Chainable {
     }
 
     /// Generates a collection of tokens from the chain, starting with the given token. This
-    /// operation is O(mn) where m is the length of the generated collection, and n is the number
-    /// of possible states from a given state. This returns an empty vector if the token is not
-    /// found.
+    /// operation is O(m) after the alias tables have been built, where m is the length of the
+    /// generated collection. This returns an empty vector if the token is not found.
     pub fn generate_from_token(&self, token: T) -> Vec<Rc<T>> {
+        let mut rng = self.rng.borrow_mut();
+        self.generate_from_token_with(token, &mut *rng)
+    }
+
+    /// Generates a collection of tokens from the chain, starting with the given token and using the
+    /// supplied random number generator. This returns an empty vector if the token is not found.
+    pub fn generate_from_token_with<R: Rng>(&self, token: T, rng: &mut R) -> Vec<Rc<T>> {
         let token = Rc::new(token);
         if !self.map.contains_key(&vec!(Some(token.clone()); self.order)) { return Vec::new() }
         let mut ret = vec![token.clone()];
         let mut curs = vec!(Some(token.clone()); self.order);
         loop {
-            let next = self.map[&curs].next();
+            // A state pruned out of the map is treated as terminal rather than indexed.
+            let next = self.map.get(&curs).and_then(|states| states.next(rng));
             curs = curs[1..self.order].to_vec();
             curs.push(next.clone());
             if let Some(next) = next { ret.push(next) };
@@ -128,6 +232,86 @@ impl<T> Chain<T> where T: Chainable {
         ret
     }
 
+    /// Feeds whitespace-delimited text into the chain, parsing each token with `R`. Words are
+    /// consumed `R::words_count()` at a time, so composite tokens are supported. Returns an error
+    /// (rather than panicking) if a token fails to parse or the input does not divide evenly into
+    /// tokens.
+    ///
+    /// ```
+    /// use markov::Chain;
+    ///
+    /// let mut chain = Chain::<u32>::new();
+    /// chain.feed_text::<u32>("3 5 10").unwrap();
+    /// ```
+    pub fn feed_text<R>(&mut self, text: &str) -> Result<&mut Chain<T>, String>
+        where R: FromTokens<Output = T> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let count = R::words_count();
+        let mut tokens = Vec::new();
+        for chunk in words.chunks(count) {
+            if chunk.len() < count {
+                return Err(format!("expected groups of {} words, found a trailing group of {}",
+                                   count, chunk.len()));
+            }
+            tokens.push(try!(R::read_words(chunk)));
+        }
+        Ok(self.feed(tokens))
+    }
+
+    /// Feeds the contents of a text file into the chain, parsing each token with `R`. See
+    /// `feed_text` for how words are grouped into tokens.
+    pub fn feed_text_file<R>(&mut self, path: &Path) -> Result<&mut Chain<T>, String>
+        where R: FromTokens<Output = T> {
+        let mut contents = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+                             .map_err(|e| format!("{}", e)));
+        self.feed_text::<R>(&contents)
+    }
+
+    /// Produces a lazy, token-by-token stream over the chain. Unlike `generate`, which eagerly
+    /// materialises a whole `Vec`, the stream samples one token per `next` call, so a caller that
+    /// only wants the first few tokens pays only for those.
+    pub fn stream(&self) -> TokenStream<T> {
+        TokenStream {
+            chain: self,
+            curs: vec!(None; self.order),
+            first: None,
+            rng: self.spawn_rng(),
+            done: false,
+        }
+    }
+
+    /// Produces a lazy stream starting from the given token, yielding that token first. The stream
+    /// is empty if the token is not a known state.
+    pub fn stream_from(&self, token: T) -> TokenStream<T> {
+        let token = Rc::new(token);
+        if self.map.contains_key(&vec!(Some(token.clone()); self.order)) {
+            TokenStream {
+                chain: self,
+                curs: vec!(Some(token.clone()); self.order),
+                first: Some(token),
+                rng: self.spawn_rng(),
+                done: false,
+            }
+        } else {
+            TokenStream {
+                chain: self,
+                curs: vec!(None; self.order),
+                first: None,
+                rng: self.spawn_rng(),
+                done: true,
+            }
+        }
+    }
+
+    /// Spawns a fresh generator for a stream, seeded from the chain's own generator so that streams
+    /// share the chain's reproducibility rather than reaching for thread-local entropy.
+    fn spawn_rng(&self) -> StdRng {
+        let mut rng = self.rng.borrow_mut();
+        let seed: Vec<usize> = (0..4).map(|_| rng.gen()).collect();
+        SeedableRng::from_seed(&seed[..])
+    }
+
     /// Produces an infinite iterator of generated token collections.
     pub fn iter(&self) -> InfiniteChainIterator<T> {
         InfiniteChainIterator { chain: self }
@@ -137,6 +321,118 @@ impl<T> Chain<T> where T: Chainable {
     pub fn iter_for(&self, size: usize) -> SizedChainIterator<T> {
         SizedChainIterator { chain: self, size: size }
     }
+
+    /// Removes successor transitions whose count is below `min_count` and drops any state left with
+    /// no successors (except the starting state, which is kept even when empty), bounding memory and keeping rare noise transitions out of generation. The
+    /// `None` sentinel transitions that terminate a sequence are always retained regardless of
+    /// their count, and the cached alias tables are invalidated so the next `generate` rebuilds
+    /// them. Pruning too aggressively can disconnect states; leave at least one reachable successor
+    /// per retained state.
+    pub fn prune(&mut self, min_count: usize) -> &mut Chain<T> {
+        let seed = vec!(None; self.order);
+        let mut empty = Vec::new();
+        for (state, states) in self.map.iter_mut() {
+            states.counts.retain(|token, &mut count| token.is_none() || count >= min_count);
+            *states.alias.borrow_mut() = None;
+            if states.counts.is_empty() && *state != seed {
+                empty.push(state.clone());
+            }
+        }
+        for state in empty {
+            self.map.remove(&state);
+        }
+        self
+    }
+
+    /// Multiplies every successor count by `factor`, aging out older training before new data is
+    /// fed — a simple online-learning decay. Counts are floored at one so a state never loses all
+    /// of its weight (use `prune` to actually remove aged-out transitions). Invalidates the cached
+    /// alias tables.
+    pub fn scale_weights(&mut self, factor: f64) {
+        assert!(factor >= 0.0);
+        for states in self.map.values_mut() {
+            for count in states.counts.values_mut() {
+                *count = ((*count as f64 * factor).round() as usize).max(1);
+            }
+            *states.alias.borrow_mut() = None;
+        }
+    }
+
+    /// Merges another chain of the same order into this one by summing the successor counts of
+    /// matching states, enabling map-reduce style training where workers build partial chains and
+    /// a coordinator combines them. Panics if the orders differ.
+    pub fn merge(&mut self, other: &Chain<T>) -> &mut Chain<T> {
+        assert_eq!(self.order, other.order, "cannot merge chains of different order");
+        for (state, states) in other.map.iter() {
+            let entry = self.map.entry(state.clone()).or_insert_with(States::new);
+            for (token, &count) in states.counts.iter() {
+                *entry.counts.entry(token.clone()).or_insert(0) += count;
+            }
+            *entry.alias.borrow_mut() = None;
+        }
+        self
+    }
+}
+
+/// The flattened, `Rc`-free snapshot written to disk. The shared `Rc<T>` interning is collapsed on
+/// save and rebuilt on load so that the deduplication is preserved without being serialised.
+#[derive(Serialize, Deserialize)]
+struct Saved<T> {
+    order: usize,
+    entries: Vec<(Vec<Option<T>>, Vec<(Option<T>, usize)>)>,
+}
+
+impl<T> Chain<T> where T: Chainable + Clone + Ord + Serialize + DeserializeOwned {
+    /// Serialises the transition map and order to `path` in a compact binary format, collapsing the
+    /// `Rc<T>` sharing so only plain values are written. States and their successors are written in
+    /// sorted order so that two saves of the same chain produce identical bytes.
+    pub fn save(&self, path: &Path) {
+        let mut entries: Vec<_> = self.map.iter().map(|(state, states)| {
+            let state: Vec<_> = state.iter().map(collapse).collect();
+            let mut successors: Vec<_> = states.counts.iter()
+                                                      .map(|(token, &count)| (collapse(token), count))
+                                                      .collect();
+            successors.sort_by(|a, b| a.0.cmp(&b.0));
+            (state, successors)
+        }).collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let saved = Saved { order: self.order, entries: entries };
+        let encoded = bincode::serialize(&saved).unwrap();
+        File::create(path).unwrap().write_all(&encoded).unwrap();
+    }
+
+    /// Loads a chain previously written with `save`, rebuilding the `Rc<T>` interning so that equal
+    /// tokens once again share a single allocation.
+    pub fn load(path: &Path) -> Chain<T> {
+        let mut bytes = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        let saved: Saved<T> = bincode::deserialize(&bytes).unwrap();
+        let mut interner = HashMap::new();
+        let mut map = HashMap::new();
+        for (state, successors) in saved.entries {
+            let state: Vec<_> = state.into_iter().map(|t| intern(&mut interner, t)).collect();
+            let mut states = States::new();
+            for (token, count) in successors {
+                states.counts.insert(intern(&mut interner, token), count);
+            }
+            map.insert(state, states);
+        }
+        Chain { map: map, order: saved.order, rng: RefCell::new(StdRng::new().unwrap()) }
+    }
+}
+
+/// Clones a shared token out into a plain value for serialisation.
+fn collapse<T: Clone>(token: &Option<Rc<T>>) -> Option<T> {
+    token.as_ref().map(|rc| (**rc).clone())
+}
+
+/// Interns a plain token back into a shared `Rc<T>`, reusing an existing allocation for equal
+/// values so the deduplication present before serialisation is restored.
+fn intern<T: Chainable + Clone>(interner: &mut HashMap<T, Rc<T>>, token: Option<T>) -> Option<Rc<T>> {
+    token.map(|t| match interner.entry(t.clone()) {
+        Occupied(e) => e.get().clone(),
+        Vacant(e) => { let rc = Rc::new(t); e.insert(rc.clone()); rc },
+    })
 }
 
 impl Chain<String> {
@@ -240,43 +536,165 @@ impl<'a, T> Iterator for InfiniteChainIterator<'a, T> where T: Chainable + 'a {
     }
 }
 
-/// A collection of states for the Markov chain.
-trait States<T: PartialEq> {
-    /// Adds a state to this states collection.
-    fn add(&mut self, token: Option<Rc<T>>);
-    /// Gets the next state from this collection of states.
-    fn next(&self) -> Option<Rc<T>>;
+/// A lazy stream of tokens sampled from a chain one at a time. Created by `Chain::stream` and
+/// `Chain::stream_from`, it holds the rolling window of the last `order` tokens and yields `None`
+/// once the terminating sentinel successor is reached.
+pub struct TokenStream<'a, T: Chainable + 'a> {
+    chain: &'a Chain<T>,
+    curs: Vec<Option<Rc<T>>>,
+    first: Option<Rc<T>>,
+    rng: StdRng,
+    done: bool,
 }
 
-impl<T> States<T> for HashMap<Option<Rc<T>>, usize> where T: Chainable {
+impl<'a, T> Iterator for TokenStream<'a, T> where T: Chainable + 'a {
+    type Item = Rc<T>;
+    fn next(&mut self) -> Option<Rc<T>> {
+        if let Some(token) = self.first.take() {
+            return Some(token);
+        }
+        if self.done { return None }
+        // A state pruned out of the map is treated as terminal rather than indexed.
+        let next = self.chain.map.get(&self.curs).and_then(|states| states.next(&mut self.rng));
+        self.curs = self.curs[1..self.chain.order].to_vec();
+        self.curs.push(next.clone());
+        match next {
+            Some(token) => Some(token),
+            None => { self.done = true; None }
+        }
+    }
+}
+
+/// Round-robins over several token streams, pulling one token from each in turn rather than
+/// draining one stream before moving to the next. This `mplus`-style fair interleaving — the same
+/// one used by relational search engines — keeps output from many starting tokens mixed even when
+/// some streams are far longer than others. Exhausted streams are dropped as they run dry.
+pub fn interleave<'a, T>(streams: Vec<TokenStream<'a, T>>) -> impl Iterator<Item = Rc<T>> + 'a
+    where T: Chainable + 'a {
+    Interleave { streams: streams, index: 0 }
+}
+
+struct Interleave<'a, T: Chainable + 'a> {
+    streams: Vec<TokenStream<'a, T>>,
+    index: usize,
+}
+
+impl<'a, T> Iterator for Interleave<'a, T> where T: Chainable + 'a {
+    type Item = Rc<T>;
+    fn next(&mut self) -> Option<Rc<T>> {
+        while !self.streams.is_empty() {
+            let i = self.index % self.streams.len();
+            match self.streams[i].next() {
+                Some(token) => {
+                    self.index = i + 1;
+                    return Some(token);
+                }
+                None => {
+                    // Removing shifts every later stream down one slot, so the next live stream
+                    // now sits at `i`; point `index` there to avoid skipping it for a round.
+                    self.streams.remove(i);
+                    self.index = i;
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The weighted set of successors that follow a single state in the chain.
+///
+/// The raw successor counts are the source of truth; a Walker alias table is built lazily the
+/// first time the state is sampled and reused until the counts change again (through `add`, or
+/// the chain's `prune`, `scale_weights` or `merge`, which clear the table). This turns
+/// each sampling step into O(1) after an O(n) build.
+#[derive(Debug)]
+struct States<T> where T: Chainable {
+    counts: HashMap<Option<Rc<T>>, usize>,
+    alias: RefCell<Option<AliasTable<T>>>,
+}
+
+/// A precomputed Walker alias table over the successors of a single state.
+#[derive(Debug)]
+struct AliasTable<T> {
+    tokens: Vec<Option<Rc<T>>>,
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+}
+
+impl<T> States<T> where T: Chainable {
+    /// Constructs an empty set of successor states.
+    fn new() -> States<T> {
+        States { counts: HashMap::new(), alias: RefCell::new(None) }
+    }
+
+    /// Records one more occurrence of `token` as a successor, invalidating any cached alias table.
     fn add(&mut self, token: Option<Rc<T>>) {
-        match self.entry(token) {
+        match self.counts.entry(token) {
             Occupied(mut e) => *e.get_mut() += 1,
             Vacant(e) => { e.insert(1); },
         }
-    }
-
-    fn next(&self) -> Option<Rc<T>> {
-        let mut sum = 0;
-        for &value in self.values() {
-            sum += value;
+        *self.alias.borrow_mut() = None;
+    }
+
+    /// Builds the alias table from the current counts using Vose's variant of the alias method.
+    fn build_alias(&self) -> AliasTable<T> {
+        let n = self.counts.len();
+        let sum: usize = self.counts.values().cloned().sum();
+        let mut tokens = Vec::with_capacity(n);
+        let mut scaled = Vec::with_capacity(n);
+        for (token, &count) in self.counts.iter() {
+            tokens.push(token.clone());
+            scaled.push(n as f64 * count as f64 / sum as f64);
         }
-        let mut rng = thread_rng();
-        let cap = rng.gen_range(0, sum);
-        sum = 0;
-        for (key, &value) in self.iter() {
-            sum += value;
-            if sum > cap {
-                return key.clone()
-            }
+        let mut prob = vec![1.0; n];
+        let mut alias = vec![0; n];
+        let (mut small, mut large) = (Vec::new(), Vec::new());
+        for (i, &q) in scaled.iter().enumerate() {
+            if q < 1.0 { small.push(i) } else { large.push(i) }
+        }
+        while !small.is_empty() && !large.is_empty() {
+            let s = small.pop().unwrap();
+            let l = large.pop().unwrap();
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] = (scaled[l] + scaled[s]) - 1.0;
+            if scaled[l] < 1.0 { small.push(l) } else { large.push(l) }
+        }
+        // Anything left in either list (single successor, all-equal weights, or rounding) keeps the
+        // default probability of 1.0 and is always accepted on its own index.
+        AliasTable { tokens, prob, alias }
+    }
+
+    /// Samples the next state, building the alias table on first use after a change. A state with
+    /// no successors (for example the seed state after aggressive pruning) samples to `None`,
+    /// terminating generation rather than panicking.
+    fn next<R: Rng>(&self, rng: &mut R) -> Option<Rc<T>> {
+        if self.counts.is_empty() { return None }
+        if self.alias.borrow().is_none() {
+            *self.alias.borrow_mut() = Some(self.build_alias());
+        }
+        let table = self.alias.borrow();
+        let table = table.as_ref().unwrap();
+        let i = rng.gen_range(0, table.tokens.len());
+        if rng.gen::<f64>() < table.prob[i] {
+            table.tokens[i].clone()
+        } else {
+            table.tokens[table.alias[i]].clone()
         }
-        unreachable!("The random number generator failed.")
+    }
+}
+
+// Two states are equal when their successor counts match; the alias table is only a cache.
+impl<T> PartialEq for States<T> where T: Chainable {
+    fn eq(&self, other: &States<T>) -> bool {
+        self.counts == other.counts
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::Chain;
+    use rand::{SeedableRng, StdRng};
 
     #[test]
     fn new() {
@@ -315,6 +733,15 @@ mod test {
         assert!([vec![3, 5, 10], vec![3, 5, 12], vec![2, 3, 5, 10], vec![2, 3, 5, 12]].contains(&v));
     }
 
+    #[test]
+    fn generate_with_is_reproducible() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]).feed(vec![3, 5, 12]);
+        let mut first = StdRng::from_seed(&[1, 2, 3, 4]);
+        let mut second = StdRng::from_seed(&[1, 2, 3, 4]);
+        assert_eq!(chain.generate_with(&mut first), chain.generate_with(&mut second));
+    }
+
     #[test]
     fn generate_from_token() {
         let mut chain = Chain::new();
@@ -331,6 +758,44 @@ mod test {
         assert_eq!(v, vec![]);
     }
 
+    #[test]
+    fn stream_from_caps_length() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let first_two: Vec<_> = chain.stream_from(3).take(2).map(|v| *v).collect();
+        assert_eq!(first_two, vec![3, 5]);
+    }
+
+    #[test]
+    fn stream_from_unfound_token_is_empty() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]);
+        assert_eq!(chain.stream_from(9).count(), 0);
+    }
+
+    #[test]
+    fn interleave_is_fair() {
+        use super::interleave;
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let streams = vec![chain.stream_from(3), chain.stream_from(5)];
+        // Fair interleaving alternates streams, so the first two tokens are each stream's head.
+        let heads: Vec<_> = interleave(streams).take(2).map(|v| *v).collect();
+        assert_eq!(heads, vec![3, 5]);
+    }
+
+    #[test]
+    fn interleave_is_fair_across_uneven_lengths() {
+        use super::interleave;
+        // Distinct tokens give each state a single successor, so every stream is deterministic.
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8]).feed(vec![2, 3, 4]).feed(vec![5, 6, 7]);
+        let streams = vec![chain.stream_from(1), chain.stream_from(2), chain.stream_from(5)];
+        let out: Vec<_> = interleave(streams).map(|v| *v).collect();
+        // The short stream drops out mid-round without the others skipping a turn.
+        assert_eq!(out, vec![1, 2, 5, 3, 6, 4, 7]);
+    }
+
     #[test]
     fn iter() {
         let mut chain = Chain::new();
@@ -345,6 +810,93 @@ mod test {
         assert_eq!(chain.iter_for(5).collect::<Vec<_>>().len(), 5);
     }
 
+    #[test]
+    fn feed_text_typed() {
+        let mut chain = Chain::<u32>::new();
+        chain.feed_text::<u32>("3 5 10").unwrap();
+        let v: Vec<_> = chain.generate_from_token(3).into_iter().map(|v| *v).collect();
+        assert_eq!(v, vec![3, 5, 10]);
+    }
+
+    #[test]
+    fn feed_text_reports_parse_error() {
+        let mut chain = Chain::<u32>::new();
+        assert!(chain.feed_text::<u32>("3 5 oops").is_err());
+    }
+
+    #[test]
+    fn prune_removes_rare_transitions() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2]).feed(vec![1, 2]).feed(vec![1, 3]);
+        chain.prune(2);
+        let v: Vec<_> = chain.generate_from_token(1).into_iter().map(|v| *v).collect();
+        assert_eq!(v, vec![1, 2]);
+    }
+
+    #[test]
+    fn prune_empty_seed_yields_empty_generation() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2]);
+        chain.prune(2);
+        assert_eq!(chain.generate().len(), 0);
+    }
+
+    #[test]
+    fn prune_does_not_panic_on_dangling_state() {
+        let mut chain = Chain::new();
+        chain.feed(vec![1u8, 2, 3]).feed(vec![1, 2, 4]);
+        // [1]->{2:2} survives but state [2] is dropped; generation must terminate, not panic.
+        chain.prune(2);
+        let v: Vec<_> = chain.generate_from_token(1).into_iter().map(|v| *v).collect();
+        assert_eq!(v, vec![1, 2]);
+    }
+
+    #[test]
+    fn scale_weights_floors_at_one() {
+        let mut chain = Chain::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        // Every count is 1, and 1 * 0.1 rounds to 0, so only the floor keeps each count at 1.
+        chain.scale_weights(0.1);
+        assert!(chain.map.values().all(|states| states.counts.values().all(|&c| c == 1)));
+        // Generation still terminates and stays on known transitions after decay.
+        let v: Vec<_> = chain.generate_from_token(3).into_iter().map(|v| *v).collect();
+        assert!([vec![3, 5, 10], vec![3, 5, 12]].contains(&v));
+    }
+
+    #[test]
+    fn merge_sums_counts() {
+        let mut a = Chain::new();
+        a.feed(vec![3u8, 5, 10]);
+        let mut b = Chain::new();
+        b.feed(vec![3u8, 5, 12]);
+        a.merge(&b);
+        // Both chains start with 3, so the starting state's count for 3 is summed to 2.
+        assert_eq!(a.map[&vec![None]].counts[&Some(::std::rc::Rc::new(3u8))], 2);
+        let v: Vec<_> = a.generate_from_token(3).into_iter().map(|v| *v).collect();
+        assert!([vec![3, 5, 10], vec![3, 5, 12]].contains(&v));
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_different_order_panics() {
+        let mut a = Chain::<u8>::new();
+        let mut b = Chain::<u8>::new();
+        b.order(2);
+        a.merge(&b);
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        use std::env::temp_dir;
+        let mut chain = Chain::<u8>::new();
+        chain.feed(vec![3u8, 5, 10]).feed(vec![5, 12]);
+        let mut path = temp_dir();
+        path.push("markov_save_load_roundtrip.bin");
+        chain.save(&path);
+        let loaded = Chain::<u8>::load(&path);
+        assert_eq!(chain, loaded);
+    }
+
     #[test]
     fn feed_str() {
         let mut chain = Chain::new();